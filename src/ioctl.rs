@@ -46,6 +46,128 @@ pub fn snap_create_v2<F: AsFd, G: AsFd, S: AsRef<OsStr>>(
     Ok(())
 }
 
+/// BTRFS_IOC_TREE_SEARCH_V2 against the quota tree.
+///
+/// Look up the `btrfs_qgroup_info_item` for qgroup `qgroupid` (the level-0
+/// qgroup id of a subvolume equals its root id) and return its `excl` field,
+/// the number of bytes exclusively owned by that subvolume. Returns `None` when
+/// no such item exists, which in practice means quotas are disabled for the
+/// filesystem.
+pub fn qgroup_excl<F: AsFd>(fd: F, qgroupid: u64) -> Result<Option<u64>> {
+    // The ioctl takes a `btrfs_ioctl_search_key`, then the `u64` size of the
+    // result buffer, then the in-out result buffer the kernel fills with
+    // header/item pairs. One qgroup info item is tiny, so a small inline buffer
+    // is plenty.
+    #[repr(C)]
+    struct Args {
+        key: btrfs::btrfs_ioctl_search_key,
+        buf_size: u64,
+        buf: [u8; 512],
+    }
+
+    // SAFETY: Zero is a valid value for every field.
+    let mut args = unsafe { mem::zeroed::<Args>() };
+    args.buf_size = args.buf.len() as u64;
+    let key = &mut args.key;
+    key.tree_id = btrfs::BTRFS_QUOTA_TREE_OBJECTID as u64;
+    key.min_type = btrfs::BTRFS_QGROUP_INFO_KEY;
+    key.max_type = btrfs::BTRFS_QGROUP_INFO_KEY;
+    // Info items are keyed `(objectid = 0, type, offset = qgroupid)`.
+    key.max_objectid = 0;
+    key.min_offset = qgroupid;
+    key.max_offset = qgroupid;
+    key.max_transid = u64::MAX;
+    key.nr_items = 1;
+
+    // BTRFS_IOC_TREE_SEARCH_V2 is an in-out ioctl: the kernel both reads the key
+    // and writes the matched items back into the same buffer, so neither
+    // `Getter` nor `Setter` fits and we drive it through a mutating `Ioctl`.
+    struct Search<'a>(&'a mut Args);
+    // SAFETY: `as_ptr` hands the kernel a pointer to `Args`, whose trailing
+    // buffer has room for the reported items; the ioctl has no extra output.
+    unsafe impl rustix::ioctl::Ioctl for Search<'_> {
+        type Output = ();
+        const IS_MUTATING: bool = true;
+        fn opcode(&self) -> rustix::ioctl::Opcode {
+            ioctl::BTRFS_IOC_TREE_SEARCH_V2
+        }
+        fn as_ptr(&mut self) -> *mut std::ffi::c_void {
+            (self.0 as *mut Args).cast()
+        }
+        unsafe fn output_from_ptr(
+            _: rustix::ioctl::IoctlOutput,
+            _: *mut std::ffi::c_void,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // SAFETY: Arguments are valid according to the doc:
+    // <https://btrfs.readthedocs.io/en/latest/btrfs-ioctl.html#btrfs-ioc-tree-search-v2>
+    unsafe { ioctl(fd, Search(&mut args))? };
+
+    if args.key.nr_items == 0 {
+        return Ok(None);
+    }
+
+    // The buffer holds a `btrfs_ioctl_search_header` followed by the item body.
+    let hdr_size = mem::size_of::<btrfs::btrfs_ioctl_search_header>();
+    // SAFETY: The kernel reported at least one item, so the header and a
+    // `btrfs_qgroup_info_item` body fit within `buf`.
+    let item = unsafe {
+        &*args
+            .buf
+            .as_ptr()
+            .add(hdr_size)
+            .cast::<btrfs::btrfs_qgroup_info_item>()
+    };
+    Ok(Some(item.excl))
+}
+
+/// BTRFS_IOC_SEND
+///
+/// Stream the subvolume behind `subvol_fd` into `out_fd` as a btrfs send stream.
+/// When `parent_root_id` is `Some`, the stream only contains the delta relative
+/// to that parent subvolume (an incremental send); `clone_sources` lists extra
+/// subvolume root ids the receiver may share extents with.
+pub fn send<F: AsFd, G: AsFd>(
+    subvol_fd: F,
+    out_fd: G,
+    parent_root_id: Option<u64>,
+    clone_sources: &[u64],
+    flags: u64,
+) -> Result<()> {
+    // SAFETY: Zero is a valid value for `btrfs_ioctl_send_args`.
+    let mut args = unsafe { mem::zeroed::<btrfs::btrfs_ioctl_send_args>() };
+    args.send_fd = out_fd.as_fd().as_raw_fd().into();
+    args.parent_root = parent_root_id.unwrap_or(0);
+    args.clone_sources = clone_sources.as_ptr().cast_mut();
+    args.clone_sources_count = clone_sources.len() as u64;
+    args.flags = flags;
+    // SAFETY: Arguments are valid according to the doc:
+    // <https://btrfs.readthedocs.io/en/latest/btrfs-ioctl.html#btrfs-ioc-send>
+    // `clone_sources` is only read by the kernel and outlives the call.
+    unsafe {
+        ioctl(subvol_fd, <Setter<{ ioctl::BTRFS_IOC_SEND }, _>>::new(args))?;
+    }
+    Ok(())
+}
+
+/// BTRFS_IOC_GET_SUBVOL_INFO
+pub fn get_subvol_info<F: AsFd>(fd: F) -> Result<btrfs::btrfs_ioctl_get_subvol_info_args> {
+    // SAFETY: Arguments are valid according to the doc:
+    // <https://btrfs.readthedocs.io/en/latest/btrfs-ioctl.html#btrfs-ioc-get-subvol-info>
+    unsafe {
+        ioctl(
+            fd,
+            <Getter<
+                { ioctl::BTRFS_IOC_GET_SUBVOL_INFO },
+                btrfs::btrfs_ioctl_get_subvol_info_args,
+            >>::new(),
+        )
+    }
+}
+
 /// BTRFS_IOC_SUBVOL_GETFLAGS
 pub fn subvol_getflags<F: AsFd>(fd: F) -> Result<u64> {
     // SAFETY: Arguments are valid according to the doc:
@@ -16,6 +16,9 @@ struct Cli {
 }
 
 #[derive(Debug, Subcommand)]
+// The subcommands differ in size (`Prune` flattens the whole `RetentionPolicy`),
+// but this enum is only ever constructed once from the parsed CLI.
+#[allow(clippy::large_enum_variant)]
 enum CliCommand {
     /// Create a BTRFS snapshot for a subvolume.
     ///
@@ -36,6 +39,22 @@ enum CliCommand {
         /// If omitted, an empty string is used.
         #[arg(long, default_value_t)]
         prefix: String,
+        /// A literal suffix appended after the timestamp in snapshot names.
+        ///
+        /// If omitted, an empty string is used.
+        #[arg(long, default_value_t)]
+        suffix: String,
+        /// The strftime-style pattern for the timestamp part of snapshot names.
+        ///
+        /// Defaults to RFC3339 with the local offset. The same pattern is used
+        /// to format new names and to parse existing ones, so it must round-trip
+        /// unambiguously; ambiguous patterns are rejected at startup. Custom
+        /// patterns are interpreted in the local time zone, which lets you adopt
+        /// filesystem-friendly names without colons (eg. `%Y-%m-%d_%H%M`).
+        /// See the supported specifiers in:
+        /// <https://docs.rs/jiff/0.2.15/jiff/fmt/strtime/index.html>
+        #[arg(long, value_name = "FORMAT")]
+        time_format: Option<String>,
         /// The source subvolume to create snapshot for.
         #[arg(long, short)]
         source: PathBuf,
@@ -47,10 +66,38 @@ enum CliCommand {
         #[arg(long)]
         skip_if_unchanged: bool,
 
+        /// Emit a machine-readable JSON report of the action to stdout.
+        #[arg(long)]
+        json: bool,
+
         /// Print the actions that would be done without doing them.
         #[arg(long)]
         dry_run: bool,
     },
+    /// Stream a BTRFS snapshot as a send stream for off-host backup.
+    ///
+    /// Serialize SOURCE as a btrfs send stream and write it to OUTPUT (or stdout
+    /// if omitted). With PARENT, only the delta since that parent snapshot is
+    /// emitted (an incremental send), which is the building block for periodic
+    /// backups on top of the `snapshot` command.
+    ///
+    /// This behaves like `btrfs send` with sugar, but does not depend on
+    /// btrfs-progs. SOURCE (and PARENT) should be read-only snapshots.
+    Send {
+        /// The source snapshot to send.
+        #[arg(long, short)]
+        source: PathBuf,
+        /// The parent snapshot to send incrementally against.
+        ///
+        /// If omitted, a full stream is produced.
+        #[arg(long, short)]
+        parent: Option<PathBuf>,
+        /// The file to write the stream to.
+        ///
+        /// If omitted, the stream is written to stdout.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
     /// Prune BTRFS snapshots according to specific retention policies.
     ///
     /// All directories under TARGET_DIR prefixed by PREFIX (empty if omitted)
@@ -85,9 +132,44 @@ enum CliCommand {
         /// If omitted, an empty string is used.
         #[arg(long, default_value_t)]
         prefix: String,
+        /// The literal suffix of snapshots to prune (see `snapshot --suffix`).
+        ///
+        /// If omitted, an empty string is used.
+        #[arg(long, default_value_t)]
+        suffix: String,
+        /// The strftime-style pattern for the timestamp part of snapshot names
+        /// (see `snapshot --time-format`).
+        ///
+        /// Must match the pattern the snapshots were created with. Defaults to
+        /// RFC3339 with the local offset.
+        #[arg(long, value_name = "FORMAT")]
+        time_format: Option<String>,
         #[command(flatten)]
         policy: RetentionPolicy,
 
+        /// Bucket snapshots into independent retention groups before pruning.
+        ///
+        /// Accepts a regular expression matched against each snapshot file name.
+        /// The substring captured by its first capture group, or the whole match
+        /// if the pattern has no capture groups, is used as the group key.
+        /// Snapshots that do not match share a single empty group.
+        ///
+        /// Each group keeps its own `--keep-*` counts, so a target directory
+        /// holding snapshots of several subvolumes (eg. `root-…`, `home-…`) can
+        /// be pruned in one invocation without a policy from one source starving
+        /// another. If omitted, all snapshots form a single group.
+        #[arg(long, value_name = "REGEX")]
+        group_by: Option<regex::Regex>,
+
+        /// Emit a machine-readable JSON report to stdout instead of the
+        /// human-readable listing.
+        ///
+        /// The report is an array of objects, one per snapshot, each with its
+        /// name, parsed `timestamp`, a boolean `delete`, and the `reasons` it is
+        /// kept for.
+        #[arg(long)]
+        json: bool,
+
         /// Print the actions that would be done without doing them.
         #[arg(long)]
         dry_run: bool,
@@ -123,6 +205,92 @@ struct RetentionPolicy {
     /// For the last N years which have one or more snapshots, keep only the most recent one for each year.
     #[arg(long, value_name = "N")]
     keep_yearly: Option<NonZero<u16>>,
+
+    /// Within SPAN before current time, keep the most recent snapshot of each hour.
+    ///
+    /// Unlike `--keep-hourly` which keeps a fixed count, this keeps one snapshot
+    /// per hour for every hour inside the window. SPAN uses the same syntax as
+    /// `--keep-within`.
+    #[arg(long, value_name = "SPAN")]
+    keep_within_hourly: Option<jiff::Span>,
+    /// Within SPAN before current time, keep the most recent snapshot of each day.
+    #[arg(long, value_name = "SPAN")]
+    keep_within_daily: Option<jiff::Span>,
+    /// Within SPAN before current time, keep the most recent snapshot of each week.
+    #[arg(long, value_name = "SPAN")]
+    keep_within_weekly: Option<jiff::Span>,
+    /// Within SPAN before current time, keep the most recent snapshot of each month.
+    #[arg(long, value_name = "SPAN")]
+    keep_within_monthly: Option<jiff::Span>,
+    /// Within SPAN before current time, keep the most recent snapshot of each year.
+    #[arg(long, value_name = "SPAN")]
+    keep_within_yearly: Option<jiff::Span>,
+
+    /// Delete the oldest otherwise-deletable snapshots until the total space
+    /// exclusively consumed by the remaining snapshots drops below SIZE.
+    ///
+    /// SIZE is a byte count with an optional binary suffix (eg. `10G`, `512M`)
+    /// or a percentage of the filesystem size (eg. `20%`). Requires btrfs
+    /// quotas to be enabled, otherwise the command errors instead of silently
+    /// keeping everything. Snapshots pinned by a `--keep-*` policy are never
+    /// counted as freeable.
+    #[arg(long, value_name = "SIZE")]
+    max_usage: Option<SizeLimit>,
+    /// Delete the oldest otherwise-deletable snapshots until at least SIZE of
+    /// the filesystem is free.
+    ///
+    /// Accepts the same SIZE syntax and quota requirement as `--max-usage`.
+    #[arg(long, value_name = "SIZE")]
+    keep_free: Option<SizeLimit>,
+}
+
+/// A retention size limit, either an absolute byte count or a percentage of the
+/// filesystem size.
+#[derive(Debug, Clone, Copy)]
+enum SizeLimit {
+    Bytes(u64),
+    Percent(f64),
+}
+
+impl SizeLimit {
+    /// Resolve the limit into an absolute byte count, given the total size of
+    /// the filesystem the snapshots live on.
+    fn to_bytes(self, fs_total: u64) -> u64 {
+        match self {
+            SizeLimit::Bytes(bytes) => bytes,
+            SizeLimit::Percent(pct) => (fs_total as f64 * pct / 100.0) as u64,
+        }
+    }
+}
+
+impl std::str::FromStr for SizeLimit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(pct) = s.strip_suffix('%') {
+            let pct = pct.trim().parse::<f64>()?;
+            ensure!(
+                (0.0..=100.0).contains(&pct),
+                "percentage must be in 0..=100"
+            );
+            return Ok(SizeLimit::Percent(pct));
+        }
+        let s = s.trim();
+        let (num, scale) = match s.as_bytes().last() {
+            Some(b'K' | b'k') => (&s[..s.len() - 1], 1u64 << 10),
+            Some(b'M' | b'm') => (&s[..s.len() - 1], 1u64 << 20),
+            Some(b'G' | b'g') => (&s[..s.len() - 1], 1u64 << 30),
+            Some(b'T' | b't') => (&s[..s.len() - 1], 1u64 << 40),
+            Some(b'B' | b'b') => (&s[..s.len() - 1], 1),
+            _ => (s, 1),
+        };
+        let bytes = num
+            .trim()
+            .parse::<u64>()?
+            .checked_mul(scale)
+            .context("size is too large")?;
+        Ok(SizeLimit::Bytes(bytes))
+    }
 }
 
 impl RetentionPolicy {
@@ -135,6 +303,76 @@ impl RetentionPolicy {
             || self.keep_weekly.is_some()
             || self.keep_monthly.is_some()
             || self.keep_yearly.is_some()
+            || self.keep_within_hourly.is_some()
+            || self.keep_within_daily.is_some()
+            || self.keep_within_weekly.is_some()
+            || self.keep_within_monthly.is_some()
+            || self.keep_within_yearly.is_some()
+            || self.max_usage.is_some()
+            || self.keep_free.is_some()
+    }
+}
+
+/// The canonical naming scheme shared by the writer (`run_snapshot`) and the
+/// reader (`list_snapshots`): `prefix` + a timestamp + `suffix`. Both sides go
+/// through this type so that names always round-trip.
+struct SnapshotNamer<'a> {
+    prefix: &'a str,
+    suffix: &'a str,
+    /// A jiff strftime pattern for the timestamp, or `None` for the default
+    /// RFC3339 with local offset.
+    time_format: Option<&'a str>,
+}
+
+impl SnapshotNamer<'_> {
+    /// Build a snapshot name for the given instant.
+    fn format(&self, now: &jiff::Zoned) -> Result<String> {
+        let time = match self.time_format {
+            None => jiff::fmt::temporal::DateTimePrinter::new()
+                .timestamp_with_offset_to_string(&now.timestamp(), now.offset()),
+            Some(fmt) => jiff::fmt::strtime::format(fmt, now)
+                .with_context(|| format!("failed to format time with --time-format {fmt:?}"))?,
+        };
+        Ok(format!("{}{}{}", self.prefix, time, self.suffix))
+    }
+
+    /// Parse the timestamp out of a snapshot file name, or `None` when the name
+    /// does not carry the configured prefix and suffix.
+    fn parse(&self, file_name: &str) -> Option<Result<jiff::Zoned>> {
+        let time = file_name
+            .strip_prefix(self.prefix)?
+            .strip_suffix(self.suffix)?;
+        Some(match self.time_format {
+            None => (|| {
+                Ok(time
+                    .parse::<jiff::Timestamp>()?
+                    .to_zoned(jiff::tz::TimeZone::system()))
+            })(),
+            // Custom patterns carry no offset, so they are read as local time.
+            Some(fmt) => (|| {
+                Ok(jiff::civil::DateTime::strptime(fmt, time)?
+                    .to_zoned(jiff::tz::TimeZone::system())?)
+            })(),
+        })
+    }
+
+    /// Reject patterns that do not round-trip, so the reader can always recover
+    /// the names the writer produces. A no-op for the default format.
+    fn validate(&self, now: &jiff::Zoned) -> Result<()> {
+        if self.time_format.is_none() {
+            return Ok(());
+        }
+        let name = self.format(now)?;
+        let reparsed = self
+            .parse(&name)
+            .context("snapshot name does not match its own prefix/suffix")??;
+        ensure!(
+            self.format(&reparsed)? == name,
+            "--time-format {:?} is ambiguous: it does not round-trip (it must \
+             unambiguously encode the date and time)",
+            self.time_format.unwrap(),
+        );
+        Ok(())
     }
 }
 
@@ -144,24 +382,65 @@ fn main() -> Result<()> {
         CliCommand::Snapshot {
             target_dir,
             prefix,
+            suffix,
+            time_format,
             source,
             skip_if_unchanged,
+            json,
             dry_run,
-        } => run_snapshot(target_dir, prefix, source, *skip_if_unchanged, *dry_run),
+        } => {
+            let namer = SnapshotNamer {
+                prefix,
+                suffix,
+                time_format: time_format.as_deref(),
+            };
+            run_snapshot(
+                target_dir,
+                &namer,
+                source,
+                *skip_if_unchanged,
+                *json,
+                *dry_run,
+            )
+        }
+        CliCommand::Send {
+            source,
+            parent,
+            output,
+        } => run_send(source, parent.as_deref(), output.as_deref()),
         CliCommand::Prune {
             target_dir,
-            prefix: name,
+            prefix,
+            suffix,
+            time_format,
             policy,
+            group_by,
+            json,
             dry_run,
-        } => run_prune(target_dir, name, policy, *dry_run),
+        } => {
+            let namer = SnapshotNamer {
+                prefix,
+                suffix,
+                time_format: time_format.as_deref(),
+            };
+            run_prune(
+                target_dir,
+                &namer,
+                policy,
+                group_by.as_ref(),
+                *json,
+                *dry_run,
+            )
+        }
     }
 }
 
 fn run_snapshot(
     target_dir: &Path,
-    prefix: &str,
+    namer: &SnapshotNamer<'_>,
     source: &Path,
     skip_if_unchanged: bool,
+    json: bool,
     dry_run: bool,
 ) -> Result<()> {
     let target_dir_fd = open_dir(None, target_dir).context("failed to open target directory")?;
@@ -174,59 +453,114 @@ fn run_snapshot(
     );
 
     let now = jiff::Zoned::now();
+    namer.validate(&now)?;
 
-    let snap_name = format!(
-        "{}{}",
-        prefix,
-        jiff::fmt::temporal::DateTimePrinter::new()
-            .timestamp_with_offset_to_string(&now.timestamp(), now.offset())
-    );
+    let snap_name = namer.format(&now)?;
     let target_path = target_dir.join(&snap_name);
 
     if skip_if_unchanged
         && let Some(latest_snap) =
-            list_snapshots(target_dir_fd.as_fd(), prefix, now.timestamp())?.first()
+            list_snapshots(target_dir_fd.as_fd(), namer, None, now.timestamp())?.first()
     {
         let snap_fd = open_dir(Some(target_dir_fd.as_fd()), latest_snap.file_name.as_ref())?;
         let snap_info = ioctl::get_subvol_info(&snap_fd)?;
         let src_info = ioctl::get_subvol_info(&subvol_fd)?;
         // (source UUID, source gen) == (snap parent UUID, snap gen at creation)
         if (src_info.uuid, src_info.generation) == (snap_info.parent_uuid, snap_info.otransid) {
-            eprintln!(
-                "source {:?} is unchanged from the latest snapshot {:?}, do nothing",
-                source.display(),
-                latest_snap.file_name,
-            );
+            if json {
+                report_snapshot(&snap_name, source, "skipped")?;
+            } else {
+                eprintln!(
+                    "source {:?} is unchanged from the latest snapshot {:?}, do nothing",
+                    source.display(),
+                    latest_snap.file_name,
+                );
+            }
             return Ok(());
         }
     }
 
     if dry_run {
-        eprintln!(
-            "would create snapshot {} for {}",
-            target_path.display(),
-            source.display()
-        );
-        eprintln!("exit without action in --dry-run mode");
+        if json {
+            report_snapshot(&snap_name, source, "would-create")?;
+        } else {
+            eprintln!(
+                "would create snapshot {} for {}",
+                target_path.display(),
+                source.display()
+            );
+            eprintln!("exit without action in --dry-run mode");
+        }
         return Ok(());
     }
 
     ioctl::snap_create_v2(&target_dir_fd, &snap_name, subvol_fd, true)
         .context("failed to create snapshot")?;
 
-    eprintln!(
-        "created snapshot {} for {}",
-        target_path.display(),
-        source.display(),
+    if json {
+        report_snapshot(&snap_name, source, "created")?;
+    } else {
+        eprintln!(
+            "created snapshot {} for {}",
+            target_path.display(),
+            source.display(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Emit a one-line JSON report of a `run_snapshot` action to stdout.
+fn report_snapshot(snap_name: &str, source: &Path, action: &str) -> Result<()> {
+    let source = source.display().to_string();
+    let report = SnapshotReport {
+        snapshot: snap_name,
+        source: &source,
+        action,
+    };
+    println!(
+        "{}",
+        serde_json::to_string(&report).context("failed to serialize report")?,
+    );
+    Ok(())
+}
+
+fn run_send(source: &Path, parent: Option<&Path>, output: Option<&Path>) -> Result<()> {
+    let subvol_fd = open_dir(None, source).context("failed to open source snapshot")?;
+    ensure!(
+        ioctl::subvol_getflags(&subvol_fd).is_ok(),
+        "{} is not a BTRFS subvolume",
+        source.display()
     );
 
+    let parent_root_id = parent
+        .map(|parent| {
+            let parent_fd = open_dir(None, parent).context("failed to open parent snapshot")?;
+            Ok::<_, anyhow::Error>(ioctl::get_subvol_info(&parent_fd)?.treeid)
+        })
+        .transpose()?;
+
+    match output {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("failed to create output file {}", path.display()))?;
+            ioctl::send(&subvol_fd, &file, parent_root_id, &[], 0)
+        }
+        None => ioctl::send(&subvol_fd, std::io::stdout(), parent_root_id, &[], 0),
+    }
+    .context("failed to send snapshot")?;
+
+    eprintln!("sent snapshot {}", source.display());
+
     Ok(())
 }
 
 fn run_prune(
     target_dir: &Path,
-    prefix: &str,
+    namer: &SnapshotNamer<'_>,
     policy: &RetentionPolicy,
+    group_by: Option<&regex::Regex>,
+    json: bool,
     dry_run: bool,
 ) -> Result<()> {
     ensure!(policy.is_valid(), "at least one policy must be provided");
@@ -234,111 +568,306 @@ fn run_prune(
         policy.keep_within.is_none_or(|dur| dur.is_positive()),
         "--keep-within only accepts a positive duration",
     );
+    ensure!(
+        [
+            policy.keep_within_hourly,
+            policy.keep_within_daily,
+            policy.keep_within_weekly,
+            policy.keep_within_monthly,
+            policy.keep_within_yearly,
+        ]
+        .iter()
+        .flatten()
+        .all(|span| span.is_positive()),
+        "--keep-within-* only accept positive durations",
+    );
+    namer.validate(&jiff::Zoned::now())?;
 
     let now = jiff::Timestamp::now();
     let target_dir_fd = open_dir(None, target_dir).context("failed to open target directory")?;
-    let mut snaps = list_snapshots(target_dir_fd.as_fd(), prefix, now)?;
+    let mut snaps = list_snapshots(target_dir_fd.as_fd(), namer, group_by, now)?;
 
     if snaps.is_empty() {
         eprintln!("no snapshot is found");
         return Ok(());
     }
 
+    // Bucket snapshots by group key before applying policies. A stable sort by
+    // group preserves the reverse-time order within each group established by
+    // `list_snapshots`, so that `chunk_by_mut` yields contiguous groups that are
+    // still sorted from latest to earliest.
+    snaps.sort_by(|a, b| a.group.cmp(&b.group));
+    for group in snaps.chunk_by_mut(|a, b| a.group == b.group) {
+        apply_policies(group, policy, now)?;
+    }
+
+    // Size-driven retention runs across all groups once the calendar policies
+    // have pinned their keeps: delete the oldest still-deletable snapshots until
+    // the disk-usage limit is met, keeping the rest.
+    if policy.max_usage.is_some() || policy.keep_free.is_some() {
+        apply_size_policy(target_dir_fd.as_fd(), &mut snaps, policy)?;
+    }
+
+    let to_delete = snaps
+        .iter()
+        .filter(|s| s.keep_reason.is_empty())
+        .map(|s| s.file_name.as_str())
+        .collect::<Vec<_>>();
+
+    // Unless a size limit is in effect, refuse to delete every snapshot. The
+    // count-based `--keep-*` policies always pin the latest snapshot of each
+    // group; the span-based ones pin nothing when every snapshot is older than
+    // the span, and this assert then aborts before anything is deleted. A size
+    // limit can legitimately require deleting every snapshot, so the check is
+    // skipped then.
+    if policy.max_usage.is_none() && policy.keep_free.is_none() {
+        assert!(
+            to_delete.len() < snaps.len(),
+            "at least one snapshot would be kept",
+        );
+    }
+
+    if json {
+        let report = snaps
+            .iter()
+            .map(|s| PruneReport {
+                snapshot: &s.file_name,
+                timestamp: s.time.timestamp(),
+                delete: s.keep_reason.is_empty(),
+                reasons: &s.keep_reason,
+            })
+            .collect::<Vec<_>>();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).context("failed to serialize report")?,
+        );
+    } else {
+        for s in &snaps {
+            let action = if s.keep_reason.is_empty() {
+                "!!delete!!".to_owned()
+            } else {
+                s.keep_reason.join(",")
+            };
+            eprintln!("{} {}", s.file_name, action);
+        }
+        eprintln!(
+            "---\nwould keep {} of {} snapshots, and delete {} snapshots.",
+            snaps.len() - to_delete.len(),
+            snaps.len(),
+            to_delete.len(),
+        );
+    }
+
+    if dry_run {
+        eprintln!("exit without action in --dry-run mode");
+        return Ok(());
+    }
+
+    if to_delete.is_empty() {
+        eprintln!("nothing to do.");
+        return Ok(());
+    }
+
+    for file_name in &to_delete {
+        ioctl::snap_destroy_v2(&target_dir_fd, file_name).with_context(|| {
+            format!(
+                "failed to delete subvolume {}",
+                target_dir.join(file_name).display(),
+            )
+        })?;
+    }
+
+    eprintln!("deleted {} snapshots (no commit).", to_delete.len());
+
+    Ok(())
+}
+
+/// Apply every retention policy to a single group of snapshots, which must be
+/// sorted from latest to earliest. Each matching snapshot records a keep reason;
+/// snapshots left with an empty reason are the ones to delete.
+fn apply_policies(
+    snaps: &mut [SnapshotInfo],
+    policy: &RetentionPolicy,
+    now: jiff::Timestamp,
+) -> Result<()> {
     if let Some(dur) = policy.keep_within {
-        let keep_since = jiff::Timestamp::now() - dur;
+        let keep_since = now - dur;
         for s in snaps
             .iter_mut()
             .take_while(|s| s.time.timestamp() >= keep_since)
         {
-            s.keep_reason.push_str(",last-within");
+            s.keep_reason.push("last-within");
         }
     }
     if let Some(last) = policy.keep_last {
         for s in snaps.iter_mut().take(last.get().into()) {
-            s.keep_reason.push_str(",last-n");
+            s.keep_reason.push("last-n");
         }
     }
 
+    // Each calendar unit keeps the most recent snapshot of every rounded bucket,
+    // bounded either by a count (`--keep-daily`) or, newest-first, until the
+    // rounded bucket falls before a cutoff (`--keep-within-daily`).
     type RoundFn = fn(&jiff::Zoned) -> Result<jiff::Zoned, jiff::Error>;
-    let calendar_policies: &[(_, _, RoundFn)] = &[
-        (",hourly", policy.keep_hourly, |t| {
-            t.with().minute(0).second(0).subsec_nanosecond(0).build()
-        }),
-        (",daily", policy.keep_daily, |t| t.start_of_day()),
-        (",weekly", policy.keep_weekly, |t| {
-            // Round to the next (exclusive) Monday at 00:00:00, treat it as the start of a (next) week.
-            // This is compatible with restic.
-            t.start_of_day()?
-                .nth_weekday(1, jiff::civil::Weekday::Monday)
-        }),
-        (",yearly", policy.keep_yearly, |t| {
-            t.start_of_day()?.first_of_year()
-        }),
+    let hourly: RoundFn = |t| t.with().minute(0).second(0).subsec_nanosecond(0).build();
+    let daily: RoundFn = |t| t.start_of_day();
+    // Round to the next (exclusive) Monday at 00:00:00, treat it as the start of
+    // a (next) week. This is compatible with restic.
+    let weekly: RoundFn = |t| {
+        t.start_of_day()?
+            .nth_weekday(1, jiff::civil::Weekday::Monday)
+    };
+    let monthly: RoundFn = |t| t.start_of_day()?.first_of_month();
+    let yearly: RoundFn = |t| t.start_of_day()?.first_of_year();
+
+    // Resolve a `--keep-within-*` span into a cutoff timestamp, using local
+    // calendar arithmetic like the rest of the calendar policies (see Note 2).
+    let now = now.to_zoned(jiff::tz::TimeZone::system());
+    let cutoff = |span: Option<jiff::Span>| -> Result<Option<jiff::Timestamp>> {
+        span.map(|span| Ok(now.checked_sub(span)?.timestamp()))
+            .transpose()
+    };
+
+    let calendar_policies: &[(&'static str, RoundFn, Option<Bound>)] = &[
+        ("hourly", hourly, policy.keep_hourly.map(count)),
+        ("daily", daily, policy.keep_daily.map(count)),
+        ("weekly", weekly, policy.keep_weekly.map(count)),
+        ("monthly", monthly, policy.keep_monthly.map(count)),
+        ("yearly", yearly, policy.keep_yearly.map(count)),
+        (
+            "within-hourly",
+            hourly,
+            cutoff(policy.keep_within_hourly)?.map(Bound::Since),
+        ),
+        (
+            "within-daily",
+            daily,
+            cutoff(policy.keep_within_daily)?.map(Bound::Since),
+        ),
+        (
+            "within-weekly",
+            weekly,
+            cutoff(policy.keep_within_weekly)?.map(Bound::Since),
+        ),
+        (
+            "within-monthly",
+            monthly,
+            cutoff(policy.keep_within_monthly)?.map(Bound::Since),
+        ),
+        (
+            "within-yearly",
+            yearly,
+            cutoff(policy.keep_within_yearly)?.map(Bound::Since),
+        ),
     ];
-    for (msg, cnt, round) in calendar_policies {
-        let Some(cnt) = cnt else { continue };
-        let mut cnt = cnt.get();
+    for (msg, round, bound) in calendar_policies {
+        let Some(bound) = bound else { continue };
+        let mut remaining = if let Bound::Count(n) = bound { *n } else { 0 };
 
         let mut last = None;
-        for s in &mut snaps {
+        for s in snaps.iter_mut() {
             let rounded = round(&s.time)
-                .with_context(|| format!("failed to round {} to unit {:?}", s.time, &msg[1..]))?
+                .with_context(|| format!("failed to round {} to unit {msg:?}", s.time))?
                 .timestamp();
             if last.replace(rounded) == Some(rounded) {
                 continue;
             }
-            s.keep_reason.push_str(msg);
-
-            cnt -= 1;
-            if cnt == 0 {
-                break;
+            match bound {
+                Bound::Count(_) => {
+                    s.keep_reason.push(*msg);
+                    remaining -= 1;
+                    if remaining == 0 {
+                        break;
+                    }
+                }
+                Bound::Since(cutoff) => {
+                    if rounded < *cutoff {
+                        break;
+                    }
+                    s.keep_reason.push(*msg);
+                }
             }
         }
     }
 
-    let mut to_delete = Vec::with_capacity(snaps.len());
-    for s in &snaps {
-        let action = if s.keep_reason.is_empty() {
-            to_delete.push(s.file_name.as_str());
-            "!!delete!!"
-        } else {
-            &s.keep_reason[1..]
-        };
-        eprintln!("{} {}", s.file_name, action);
-    }
+    Ok(())
+}
 
-    assert!(
-        to_delete.len() < snaps.len(),
-        "at least one snapshot would be kept",
-    );
+/// Bounds a calendar retention policy: keep a fixed number of buckets, or keep
+/// every bucket whose rounded time is at or after a cutoff.
+enum Bound {
+    Count(u16),
+    Since(jiff::Timestamp),
+}
 
-    eprintln!(
-        "---\nwould keep {} of {} snapshots, and delete {} snapshots.",
-        snaps.len() - to_delete.len(),
-        snaps.len(),
-        to_delete.len(),
-    );
+fn count(n: NonZero<u16>) -> Bound {
+    Bound::Count(n.get())
+}
 
-    if dry_run {
-        eprintln!("exit without action in --dry-run mode");
-        return Ok(());
+/// Enforce the `--max-usage`/`--keep-free` limits by deleting the oldest
+/// snapshots that are not already pinned by a calendar policy, until the limit
+/// is satisfied. Surviving deletable snapshots are marked as kept. Errors if
+/// quotas are disabled so usage cannot be measured.
+fn apply_size_policy(
+    target_dir_fd: BorrowedFd<'_>,
+    snaps: &mut [SnapshotInfo],
+    policy: &RetentionPolicy,
+) -> Result<()> {
+    // Exclusive bytes per snapshot, read from the quota tree.
+    let mut excls = Vec::with_capacity(snaps.len());
+    for s in snaps.iter() {
+        let snap_fd = open_dir(Some(target_dir_fd), s.file_name.as_ref())?;
+        let treeid = ioctl::get_subvol_info(&snap_fd)?.treeid;
+        let excl = ioctl::qgroup_excl(target_dir_fd, treeid)
+            .with_context(|| format!("failed to read qgroup usage of {:?}", s.file_name))?
+            .with_context(|| {
+                format!(
+                    "btrfs quotas are disabled: no qgroup usage for {:?}; \
+                     enable them with `btrfs quota enable`",
+                    s.file_name,
+                )
+            })?;
+        excls.push(excl);
     }
 
-    if to_delete.is_empty() {
-        eprintln!("nothing to do.");
-        return Ok(());
+    let stat = rustix::fs::fstatvfs(target_dir_fd).context("failed to stat filesystem")?;
+    let fs_total = stat.f_frsize * stat.f_blocks;
+    let fs_free = stat.f_frsize * stat.f_bavail;
+    let total_excl: u64 = excls.iter().sum();
+
+    // Bytes we must free to satisfy the strictest configured limit.
+    let mut need = 0u64;
+    if let Some(limit) = policy.max_usage {
+        need = need.max(total_excl.saturating_sub(limit.to_bytes(fs_total)));
+    }
+    if let Some(limit) = policy.keep_free {
+        need = need.max(limit.to_bytes(fs_total).saturating_sub(fs_free));
     }
 
-    for file_name in &to_delete {
-        ioctl::snap_destroy_v2(&target_dir_fd, file_name).with_context(|| {
-            format!(
-                "failed to delete subvolume {}",
-                target_dir.join(file_name).display(),
-            )
-        })?;
+    // Deletable candidates, oldest first: the oldest are freed first.
+    let mut candidates = (0..snaps.len())
+        .filter(|&i| snaps[i].keep_reason.is_empty())
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|&i| snaps[i].time.timestamp());
+
+    let mut freed = 0u64;
+    for &i in &candidates {
+        if freed >= need {
+            // Enough freed already; keep this (and every newer) candidate.
+            snaps[i].keep_reason.push("usage");
+        } else {
+            freed += excls[i];
+        }
     }
 
-    eprintln!("deleted {} snapshots (no commit).", to_delete.len());
+    if freed < need {
+        eprintln!(
+            "warning: deleting all {} unpinned snapshots only frees {} bytes, {} bytes short of the limit",
+            candidates.len(),
+            freed,
+            need - freed,
+        );
+    }
 
     Ok(())
 }
@@ -346,16 +875,40 @@ fn run_prune(
 struct SnapshotInfo {
     file_name: String,
     time: jiff::Zoned,
-    /// Why this snapshot should be kept. Empty means to-be-deleted.
+    /// The retention group this snapshot belongs to, extracted from its file
+    /// name via `--group-by`. An empty string when grouping is disabled, so
+    /// that all snapshots fall into a single group.
     /// Only used in `run_prune`.
-    keep_reason: String,
+    group: String,
+    /// Why this snapshot should be kept, as a list of policy tags (eg. `daily`,
+    /// `last-n`). Empty means to-be-deleted. Only used in `run_prune`.
+    keep_reason: Vec<&'static str>,
+}
+
+/// A per-snapshot entry of the `run_prune --json` report, modeled on rustic's
+/// `ForgetSnapshot`.
+#[derive(serde::Serialize)]
+struct PruneReport<'a> {
+    snapshot: &'a str,
+    timestamp: jiff::Timestamp,
+    delete: bool,
+    reasons: &'a [&'static str],
+}
+
+/// The `run_snapshot --json` report.
+#[derive(serde::Serialize)]
+struct SnapshotReport<'a> {
+    snapshot: &'a str,
+    source: &'a str,
+    action: &'a str,
 }
 
-/// List all existing snapshots in `target_dir` has `prefix`,
+/// List all existing snapshots in `target_dir` matching `namer`,
 /// sorted by creation time from latest to earliest.
 fn list_snapshots(
     target_dir_fd: BorrowedFd<'_>,
-    prefix: &str,
+    namer: &SnapshotNamer<'_>,
+    group_by: Option<&regex::Regex>,
     now: jiff::Timestamp,
 ) -> Result<Vec<SnapshotInfo>> {
     let mut snaps = Vec::new();
@@ -369,19 +922,15 @@ fn list_snapshots(
         if !ent.file_type().is_dir() || [&b"."[..], b".."].contains(&file_name.to_bytes()) {
             continue;
         }
-        let Some(suffix) = file_name.to_bytes().strip_prefix(prefix.as_bytes()) else {
+        // Non-UTF-8 names can never carry the (UTF-8) prefix/suffix template.
+        let Ok(file_name) = file_name.to_str() else {
             continue;
         };
-
-        let time = (|| -> Result<_> {
-            Ok(str::from_utf8(suffix)?
-                .parse::<jiff::Timestamp>()?
-                .to_zoned(jiff::tz::TimeZone::system()))
-        })()
-        .with_context(|| {
-            format!("failed to parse time from name: {file_name:?} (prefix: {prefix:?})")
-        })?;
-        let file_name = file_name.to_str().expect("checked to be UTF-8");
+        let Some(time) = namer.parse(file_name) else {
+            continue;
+        };
+        let time =
+            time.with_context(|| format!("failed to parse time from name: {file_name:?}"))?;
 
         ensure!(
             open_dir(Some(target_dir_fd), file_name.as_ref())
@@ -395,10 +944,20 @@ fn list_snapshots(
             continue;
         }
 
+        // Extract the retention group key: the first capture group of the
+        // pattern, or the whole match if it has none. A non-matching name gets
+        // an empty key, sharing a single empty group.
+        let group = group_by
+            .and_then(|re| re.captures(file_name))
+            .map(|caps| caps.get(1).unwrap_or_else(|| caps.get(0).unwrap()).as_str())
+            .unwrap_or("")
+            .to_owned();
+
         snaps.push(SnapshotInfo {
             file_name: file_name.to_owned(),
             time,
-            keep_reason: String::new(),
+            group,
+            keep_reason: Vec::new(),
         });
     }
 